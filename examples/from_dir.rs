@@ -1,6 +1,6 @@
 use compact_path_tree::{CompactPathTree, PathVisitor};
 use std::env::args;
-use std::fs::DirEntry;
+use std::fs::{DirEntry, FileType};
 use std::io;
 use std::path::PathBuf;
 use std::time::Instant;
@@ -15,21 +15,19 @@ struct Stats {
 }
 
 impl PathVisitor for Stats {
-    fn visit(&mut self, entry: &DirEntry) -> io::Result<()> {
-        // note: on unix this call is non-trivial and can dramatically hurt
-        // performance
-        let meta = entry.metadata()?;
-
-        if meta.file_type().is_file() {
+    fn visit(&mut self, entry: &DirEntry, file_type: FileType, _depth: usize) -> io::Result<()> {
+        if file_type.is_file() {
             self.files += 1;
-        } else if meta.file_type().is_dir() {
+        } else if file_type.is_dir() {
             self.dirs += 1;
-        } else if meta.file_type().is_symlink() {
+        } else if file_type.is_symlink() {
             self.symlinks += 1;
         }
 
         self.items += 1;
-        self.bytes += meta.len();
+        // note: on unix this call is non-trivial and can dramatically hurt
+        // performance, but we need it for the entry's size
+        self.bytes += entry.metadata()?.len();
 
         Ok(())
     }
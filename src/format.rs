@@ -0,0 +1,268 @@
+//! A packed binary encoding for [`CompactPathTree`], letting an expensive
+//! scan be cached to disk and reloaded without touching the filesystem
+//! again.
+//!
+//! The format mirrors the `Normal`/`ParentDir` component stream the tree is
+//! already stored as and the iterator already walks: each directory name
+//! encountered is written as a length-prefixed record tagged with an opcode
+//! saying whether it's a `Descend` (a directory with further records nested
+//! inside it) or a `Leaf` (a file, or an empty directory, with nothing
+//! nested inside it), and every `Descend` is eventually closed by a matching
+//! `Ascend` record. This is considerably smaller and faster to parse than
+//! re-serializing full paths, since shared path prefixes are only ever
+//! written once.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::CompactPathTree;
+
+/// The only format version emitted and accepted so far. Bumped whenever the
+/// on-disk layout changes incompatibly.
+const FORMAT_VERSION: u8 = 1;
+
+const OPCODE_DESCEND: u8 = 0;
+const OPCODE_LEAF: u8 = 1;
+const OPCODE_ASCEND: u8 = 2;
+
+#[cfg(unix)]
+pub(crate) fn os_str_to_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn os_str_to_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    Cow::Owned(s.to_string_lossy().into_owned().into_bytes())
+}
+
+#[cfg(unix)]
+fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn read_bytes(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+impl CompactPathTree {
+    /// Write this tree to `w` in the packed binary format described by
+    /// [this module](self).
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&[FORMAT_VERSION])?;
+        write_bytes(&mut w, &os_str_to_bytes(self.root.as_os_str()))?;
+
+        let mut components = self.path.components().peekable();
+        while let Some(c) = components.next() {
+            match c {
+                Component::Normal(name) => {
+                    let bytes = os_str_to_bytes(name);
+                    if matches!(components.peek(), Some(Component::ParentDir)) {
+                        components.next();
+                        w.write_all(&[OPCODE_LEAF])?;
+                    } else {
+                        w.write_all(&[OPCODE_DESCEND])?;
+                    }
+                    write_bytes(&mut w, &bytes)?;
+                }
+                Component::ParentDir => w.write_all(&[OPCODE_ASCEND])?,
+                c => unreachable!("illegal component {:?} in path tree", c),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a tree previously written with [`CompactPathTree::write_to`].
+    pub fn read_from<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut version = [0u8; 1];
+        r.read_exact(&mut version)?;
+        if version[0] != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported compact_path_tree format version {} (expected {})",
+                    version[0], FORMAT_VERSION
+                ),
+            ));
+        }
+
+        let root = PathBuf::from(bytes_to_os_string(read_bytes(&mut r)?));
+        let mut path = PathBuf::new();
+        // tracks how many `Descend`s are currently unmatched by an `Ascend`,
+        // so a truncated or corrupted stream (a stray `Ascend`, or one left
+        // dangling at EOF) is rejected instead of silently accepted.
+        let mut open: usize = 0;
+
+        loop {
+            let mut opcode = [0u8; 1];
+            match r.read_exact(&mut opcode) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+
+            match opcode[0] {
+                OPCODE_DESCEND => {
+                    let name = bytes_to_os_string(read_bytes(&mut r)?);
+                    path.push(Component::Normal(&name).as_os_str());
+                    open += 1;
+                }
+                OPCODE_LEAF => {
+                    let name = bytes_to_os_string(read_bytes(&mut r)?);
+                    path.push(Component::Normal(&name).as_os_str());
+                    path.push(Component::ParentDir.as_os_str());
+                }
+                OPCODE_ASCEND => {
+                    open = open.checked_sub(1).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "compact_path_tree stream has an ascend with no matching descend",
+                        )
+                    })?;
+                    path.push(Component::ParentDir.as_os_str());
+                }
+                op => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown compact_path_tree opcode {}", op),
+                    ))
+                }
+            }
+        }
+
+        if open != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("compact_path_tree stream has {} unclosed descend(s)", open),
+            ));
+        }
+
+        path.shrink_to_fit();
+        Ok(Self { root, path })
+    }
+
+    /// Write this tree to the file at `path` in the packed binary format,
+    /// overwriting it if it already exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read a tree previously written with [`CompactPathTree::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a tree (without touching the filesystem) equivalent to:
+    /// `a/file.txt`, `b` (an empty leaf, e.g. an empty directory or a file).
+    fn sample_tree() -> CompactPathTree {
+        let mut path = PathBuf::new();
+        path.push(Component::Normal("a".as_ref()));
+        path.push(Component::Normal("file.txt".as_ref()));
+        path.push(Component::ParentDir);
+        path.push(Component::ParentDir);
+        path.push(Component::Normal("b".as_ref()));
+        path.push(Component::ParentDir);
+
+        CompactPathTree {
+            root: PathBuf::from("/tmp/root"),
+            path,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_write_to_and_read_from() {
+        let tree = sample_tree();
+
+        let mut buf = Vec::new();
+        tree.write_to(&mut buf).unwrap();
+
+        let read_back = CompactPathTree::read_from(&buf[..]).unwrap();
+        assert!(tree == read_back);
+    }
+
+    #[test]
+    fn rejects_mismatched_version() {
+        let mut buf = Vec::new();
+        sample_tree().write_to(&mut buf).unwrap();
+        buf[0] = FORMAT_VERSION.wrapping_add(1);
+
+        let err = match CompactPathTree::read_from(&buf[..]) {
+            Err(e) => e,
+            Ok(_) => panic!("expected a version mismatch error"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_unknown_opcode() {
+        let mut buf = Vec::new();
+        sample_tree().write_to(&mut buf).unwrap();
+
+        // the first record right after the version byte and root path is an
+        // opcode byte; corrupt it to a value no opcode uses.
+        let root_len = 1 + 4 + b"/tmp/root".len();
+        buf[root_len] = 0xff;
+
+        let err = match CompactPathTree::read_from(&buf[..]) {
+            Err(e) => e,
+            Ok(_) => panic!("expected an unknown-opcode error"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_stray_ascend() {
+        let mut buf = Vec::new();
+        buf.push(FORMAT_VERSION);
+        write_bytes(&mut buf, b"/tmp/root").unwrap();
+        buf.push(OPCODE_ASCEND);
+
+        let err = match CompactPathTree::read_from(&buf[..]) {
+            Err(e) => e,
+            Ok(_) => panic!("expected a stray-ascend error"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_unclosed_descend() {
+        let mut buf = Vec::new();
+        buf.push(FORMAT_VERSION);
+        write_bytes(&mut buf, b"/tmp/root").unwrap();
+        buf.push(OPCODE_DESCEND);
+        write_bytes(&mut buf, b"a").unwrap();
+        // stream ends here with no matching `OPCODE_ASCEND` for "a"
+
+        let err = match CompactPathTree::read_from(&buf[..]) {
+            Err(e) => e,
+            Ok(_) => panic!("expected an unclosed-descend error"),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,382 @@
+//! A [`PathVisitor`] wrapper that prunes entries matched by `.gitignore`/
+//! `.ignore` files encountered during traversal, mirroring how gitignore-
+//! aware walkers prune trees.
+//!
+//! Since pruning happens in `filter`, an ignored directory's `read_dir` is
+//! never called at all, shrinking both the stored tree and the time spent
+//! building it on repositories with large ignored build/target directories.
+
+use std::collections::HashMap;
+use std::fs::{self, DirEntry, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::PathVisitor;
+
+/// A single compiled rule from a `.gitignore`/`.ignore` file.
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// `true` for a `!`-prefixed rule, which re-includes a previously
+    /// ignored path instead of ignoring it.
+    negate: bool,
+    /// `true` for a trailing-`/` rule, which only matches directories.
+    dir_only: bool,
+    /// `true` if the rule contains a `/` other than a trailing one, which
+    /// anchors it to the directory the rule came from rather than letting
+    /// it match at any depth beneath it.
+    anchored: bool,
+    /// The glob itself, with any leading/trailing `/` already stripped.
+    glob: String,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut rule = line;
+        let negate = rule.starts_with('!');
+        if negate {
+            rule = &rule[1..];
+        }
+
+        let dir_only = rule.ends_with('/');
+        if dir_only {
+            rule = &rule[..rule.len() - 1];
+        }
+
+        if rule.is_empty() {
+            return None;
+        }
+
+        let anchored = rule.contains('/');
+        let glob = rule.strip_prefix('/').unwrap_or(rule).to_string();
+
+        Some(Pattern {
+            negate,
+            dir_only,
+            anchored,
+            glob,
+        })
+    }
+
+    fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            let pattern: Vec<&str> = self.glob.split('/').collect();
+            let path: Vec<&str> = rel_path.split('/').filter(|c| !c.is_empty()).collect();
+            components_match(&pattern, &path)
+        } else {
+            // an unanchored rule has no `/` in it, so it's matched against
+            // the final path component at any depth
+            rel_path
+                .rsplit('/')
+                .next()
+                .is_some_and(|name| segment_match(&self.glob, name))
+        }
+    }
+}
+
+// both matchers below used to be naive recursive backtracking, which is
+// exponential in the number of `*`/`**` wildcards against non-matching
+// input (classic catastrophic backtracking) - a single adversarial
+// `.gitignore` line could hang traversal indefinitely. `components_match` is
+// now bottom-up dynamic programming and `segment_match` a greedy two-pointer
+// matcher, both polynomial in the lengths being compared no matter how many
+// wildcards a rule contains.
+
+/// Matches a sequence of path components against a sequence of pattern
+/// segments, where a `"**"` segment matches zero or more path components.
+fn components_match(pattern: &[&str], path: &[&str]) -> bool {
+    let plen = pattern.len();
+    let tlen = path.len();
+
+    // dp[i][j] == does `pattern[i..]` match `path[j..]`
+    let mut dp = vec![vec![false; tlen + 1]; plen + 1];
+    dp[plen][tlen] = true;
+
+    for i in (0..=plen).rev() {
+        for j in (0..=tlen).rev() {
+            if i == plen && j == tlen {
+                continue;
+            }
+
+            dp[i][j] = if i == plen {
+                false
+            } else if pattern[i] == "**" {
+                dp[i + 1][j] || (j < tlen && dp[i][j + 1])
+            } else {
+                j < tlen && segment_match(pattern[i], path[j]) && dp[i + 1][j + 1]
+            };
+        }
+    }
+
+    dp[0][0]
+}
+
+/// Matches a single path segment against a glob containing `*` and `?`,
+/// using the standard greedy two-pointer wildcard matching algorithm: `ti`
+/// and `pi` advance together on a literal/`?` match, and a `*` is recorded
+/// so that on a later mismatch we can backtrack to just after it and retry
+/// with one more character of `text` consumed, instead of re-exploring
+/// every possible split recursively.
+fn segment_match(pattern: &str, text: &str) -> bool {
+    let p = pattern.as_bytes();
+    let t = text.as_bytes();
+
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut star_match = 0;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some(pi);
+            star_match = ti;
+            pi += 1;
+        } else if let Some(star_pi) = star {
+            star_match += 1;
+            pi = star_pi + 1;
+            ti = star_match;
+        } else {
+            return false;
+        }
+    }
+
+    while p.get(pi) == Some(&b'*') {
+        pi += 1;
+    }
+
+    pi == p.len()
+}
+
+/// The compiled rules contributed by a single directory's `.gitignore` and
+/// `.ignore` files.
+#[derive(Default)]
+struct DirRules {
+    patterns: Vec<Pattern>,
+}
+
+fn load_dir_rules(dir: &Path) -> DirRules {
+    let mut patterns = Vec::new();
+
+    for name in [".gitignore", ".ignore"] {
+        if let Ok(contents) = fs::read_to_string(dir.join(name)) {
+            patterns.extend(contents.lines().filter_map(Pattern::parse));
+        }
+    }
+
+    DirRules { patterns }
+}
+
+/// Wraps any [`PathVisitor`], pruning entries ignored by `.gitignore`/
+/// `.ignore` files found while walking their containing directories.
+///
+/// Rules are applied with the usual gitignore precedence: rules from deeper
+/// directories override rules from shallower ones, later rules within a
+/// file override earlier ones, and a `!`-prefixed rule re-includes a path
+/// otherwise ignored by an earlier rule.
+pub struct IgnoreVisitor<V> {
+    inner: V,
+    hidden: bool,
+    root: PathBuf,
+    rules: HashMap<PathBuf, Rc<DirRules>>,
+    /// The ancestor-directory stack applicable to each directory we've seen,
+    /// keyed by that directory. Every entry within the same directory shares
+    /// an identical stack, so this is computed once per directory, the first
+    /// time an entry in it is filtered, and kept for the rest of the
+    /// traversal rather than being rebuilt from `root` for every single
+    /// entry.
+    stacks: HashMap<PathBuf, Rc<Vec<PathBuf>>>,
+}
+
+impl<V: PathVisitor> IgnoreVisitor<V> {
+    /// Wrap `inner`, applying `.gitignore`/`.ignore` rules found at or below
+    /// `root` - this should be the same root the tree is built from.
+    pub fn new(root: impl Into<PathBuf>, inner: V) -> Self {
+        Self {
+            inner,
+            hidden: false,
+            root: root.into(),
+            rules: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// When enabled, entries whose file name starts with `.` are pruned,
+    /// independent of any `.gitignore`/`.ignore` rules.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    fn rules_for(&mut self, dir: &Path) -> Rc<DirRules> {
+        Rc::clone(
+            self.rules
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| Rc::new(load_dir_rules(dir))),
+        )
+    }
+
+    /// The directories from `self.root` down to (and including) `dir`, in
+    /// that order, i.e. the directories whose rules apply within `dir`.
+    fn stack_for(&mut self, dir: &Path) -> Rc<Vec<PathBuf>> {
+        if let Some(stack) = self.stacks.get(dir) {
+            return Rc::clone(stack);
+        }
+
+        let stack = match dir.parent() {
+            Some(parent) if dir != self.root && parent.starts_with(&self.root) => {
+                let mut stack = (*self.stack_for(parent)).clone();
+                stack.push(dir.to_path_buf());
+                stack
+            }
+            _ => vec![self.root.clone()],
+        };
+
+        let stack = Rc::new(stack);
+        self.stacks.insert(dir.to_path_buf(), Rc::clone(&stack));
+        stack
+    }
+
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let dir = match path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => return false,
+        };
+
+        let mut ignored = false;
+        for ancestor in self.stack_for(&dir).iter() {
+            let rel = path.strip_prefix(ancestor).unwrap_or(path);
+            let rel = rel.to_string_lossy();
+
+            for pattern in &self.rules_for(ancestor).patterns {
+                if pattern.matches(&rel, is_dir) {
+                    ignored = !pattern.negate;
+                }
+            }
+        }
+
+        ignored
+    }
+}
+
+impl<V: PathVisitor> PathVisitor for IgnoreVisitor<V> {
+    fn filter(&mut self, entry: &DirEntry, file_type: FileType, depth: usize) -> io::Result<bool> {
+        if self.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            return Ok(false);
+        }
+
+        if self.is_ignored(&entry.path(), file_type.is_dir()) {
+            return Ok(false);
+        }
+
+        self.inner.filter(entry, file_type, depth)
+    }
+
+    fn visit(&mut self, entry: &DirEntry, file_type: FileType, depth: usize) -> io::Result<()> {
+        self.inner.visit(entry, file_type, depth)
+    }
+
+    fn handle_error(
+        &mut self,
+        error: io::Error,
+        directory: &Path,
+        entry: Option<&DirEntry>,
+    ) -> Option<io::Error> {
+        self.inner.handle_error(error, directory, entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactPathTreeBuilder, PathVisitor};
+    use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+
+    /// A directory under `std::env::temp_dir()`, removed when dropped, so
+    /// these tests can exercise real `.gitignore` files without an external
+    /// crate for temp-file handling.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "compact_path_tree_ignore_test_{}_{}_{}",
+                std::process::id(),
+                name,
+                n
+            ));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A visitor that accepts everything, relying entirely on
+    /// `PathVisitor`'s default method bodies.
+    struct AcceptAll;
+    impl PathVisitor for AcceptAll {}
+
+    fn build(root: &Path) -> Vec<PathBuf> {
+        let tree = CompactPathTreeBuilder::new()
+            .build(
+                root.to_path_buf(),
+                &mut IgnoreVisitor::new(root.to_path_buf(), AcceptAll),
+            )
+            .unwrap();
+        tree.iter().collect()
+    }
+
+    #[test]
+    fn deeper_rules_override_shallower_ones_via_negation() {
+        let dir = TempDir::new("precedence");
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/.gitignore"), "!keep.log\n").unwrap();
+        fs::write(dir.path().join("app.log"), b"x").unwrap();
+        fs::write(dir.path().join("sub/app.log"), b"x").unwrap();
+        fs::write(dir.path().join("sub/keep.log"), b"x").unwrap();
+
+        let paths = build(dir.path());
+
+        assert!(!paths.contains(&dir.path().join("app.log")));
+        assert!(!paths.contains(&dir.path().join("sub/app.log")));
+        assert!(paths.contains(&dir.path().join("sub/keep.log")));
+    }
+
+    #[test]
+    fn dir_only_rules_only_match_directories() {
+        let dir = TempDir::new("dir_only");
+        fs::write(dir.path().join(".gitignore"), "out/\n").unwrap();
+        fs::create_dir(dir.path().join("out")).unwrap();
+        fs::write(dir.path().join("out/file.txt"), b"x").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/out"), b"x").unwrap();
+
+        let paths = build(dir.path());
+
+        assert!(!paths.contains(&dir.path().join("out")));
+        assert!(!paths.contains(&dir.path().join("out/file.txt")));
+        assert!(paths.contains(&dir.path().join("sub/out")));
+    }
+}
@@ -0,0 +1,305 @@
+//! A random-access index over a [`CompactPathTree`]'s component stream.
+//!
+//! The tree itself is one linear stream of `Normal`/`ParentDir` components,
+//! so without an index the only way to find a path or enumerate one
+//! directory's descendants is [`CompactPathTree::iter`], which scans
+//! everything. [`CompactPathTreeIndex`] instead records, for each `Normal`
+//! component, where its subtree begins and ends in the stream, and links
+//! siblings together, so a lookup only has to walk the query path's own
+//! components and scan the siblings at each level - roughly `O(depth *
+//! fanout)` instead of `O(total entries)`.
+
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{CompactPathTree, CompactPathTreeIter};
+
+/// One `Normal` component of the tree, with enough bookkeeping to walk to
+/// its siblings or descend into its children without rescanning the whole
+/// stream.
+struct Node<'a> {
+    name: &'a OsStr,
+    /// Position, in the component stream, of the first token that could
+    /// belong to a child of this node.
+    content_start: usize,
+    /// Position, in the component stream, of the `ParentDir` token that
+    /// closes this node - i.e. one past the last token that belongs to a
+    /// descendant of this node.
+    content_end: usize,
+    first_child: Option<u32>,
+    next_sibling: Option<u32>,
+}
+
+/// An index over a [`CompactPathTree`], supporting random-access lookups.
+/// Built with [`CompactPathTree::index`].
+pub struct CompactPathTreeIndex<'a> {
+    tree: &'a CompactPathTree,
+    nodes: Vec<Node<'a>>,
+    root_first_child: Option<u32>,
+    /// Total number of tokens in the component stream, i.e. the `content_end`
+    /// of an implicit root node.
+    total_tokens: usize,
+}
+
+impl<'a> CompactPathTreeIndex<'a> {
+    pub(crate) fn build(tree: &'a CompactPathTree) -> Self {
+        let mut nodes = Vec::new();
+        // `open[i]` is the node index of the ancestor at depth `i`, and
+        // `last_child[i]` is the last child added so far at depth `i` (`i ==
+        // 0` is the root level, which has no owning node).
+        let mut open: Vec<u32> = Vec::new();
+        let mut last_child: Vec<Option<u32>> = vec![None];
+        let mut root_first_child = None;
+        let mut token = 0usize;
+
+        for component in tree.path.components() {
+            match component {
+                Component::Normal(name) => {
+                    let idx = nodes.len() as u32;
+                    nodes.push(Node {
+                        name,
+                        content_start: 0,
+                        content_end: 0,
+                        first_child: None,
+                        next_sibling: None,
+                    });
+
+                    let depth = open.len();
+                    match last_child[depth] {
+                        Some(sibling) => nodes[sibling as usize].next_sibling = Some(idx),
+                        None => match open.last() {
+                            Some(&parent) => nodes[parent as usize].first_child = Some(idx),
+                            None => root_first_child = Some(idx),
+                        },
+                    }
+                    last_child[depth] = Some(idx);
+
+                    token += 1;
+                    nodes[idx as usize].content_start = token;
+                    open.push(idx);
+                    last_child.push(None);
+                }
+                Component::ParentDir => {
+                    // a well-formed tree never has more `ParentDir`s than
+                    // `Normal`s at any point, but a tree loaded from a
+                    // corrupted or hand-crafted stream might (this should no
+                    // longer be reachable through `CompactPathTree::read_from`
+                    // itself, which now rejects unbalanced streams, but the
+                    // index shouldn't also panic on untrusted input as a
+                    // second line of defense). stop indexing at the point of
+                    // desync rather than panicking; everything indexed so far
+                    // is still valid.
+                    let idx = match open.pop() {
+                        Some(idx) => idx,
+                        None => break,
+                    };
+                    nodes[idx as usize].content_end = token;
+                    last_child.pop();
+                    token += 1;
+                }
+                c => unreachable!("illegal component {:?} in path tree", c),
+            }
+        }
+
+        Self {
+            tree,
+            nodes,
+            root_first_child,
+            total_tokens: token,
+        }
+    }
+
+    /// Find the node for `path`, if it's stored in the tree.
+    fn find(&self, path: &Path) -> Option<u32> {
+        let rel = path.strip_prefix(&self.tree.root).ok()?;
+        let mut siblings = self.root_first_child;
+        let mut found = None;
+
+        for component in rel.components() {
+            let name = match component {
+                Component::Normal(name) => name,
+                _ => return None,
+            };
+
+            found = None;
+            let mut cursor = siblings;
+            while let Some(idx) = cursor {
+                let node = &self.nodes[idx as usize];
+                if node.name == name {
+                    found = Some(idx);
+                    break;
+                }
+                cursor = node.next_sibling;
+            }
+
+            let idx = found?;
+            siblings = self.nodes[idx as usize].first_child;
+        }
+
+        found
+    }
+
+    /// Does the tree contain `path`?
+    pub fn contains(&self, path: &Path) -> bool {
+        path == self.tree.root || self.find(path).is_some()
+    }
+
+    /// Iterate over the direct children of `path` (not further descendants),
+    /// without scanning anything outside of `path` itself.
+    ///
+    /// Yields nothing if `path` isn't in the tree or has no children.
+    pub fn children_of(&self, path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+        let first = if path == self.tree.root {
+            self.root_first_child
+        } else {
+            self.find(path)
+                .and_then(|idx| self.nodes[idx as usize].first_child)
+        };
+
+        let base = path.to_path_buf();
+        std::iter::successors(first, move |&idx| self.nodes[idx as usize].next_sibling)
+            .map(move |idx| base.join(self.nodes[idx as usize].name))
+    }
+
+    /// Iterate over every descendant of `path` (not including `path`
+    /// itself), bounded to its range in the component stream. The
+    /// components before that range are still stepped over one by one from
+    /// the start of the stream, though no paths are built for them.
+    ///
+    /// Yields nothing if `path` isn't in the tree.
+    pub fn subtree(&self, path: &Path) -> CompactPathTreeIter<'a> {
+        let range = if path == self.tree.root {
+            Some((0, self.total_tokens))
+        } else {
+            self.find(path).map(|idx| {
+                (
+                    self.nodes[idx as usize].content_start,
+                    self.nodes[idx as usize].content_end,
+                )
+            })
+        };
+
+        let (start, end) = range.unwrap_or((0, 0));
+
+        let mut components = self.tree.path.components();
+        for _ in 0..start {
+            components.next();
+        }
+
+        CompactPathTreeIter::bounded(path.to_path_buf(), components, end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a tree (without touching the filesystem) equivalent to:
+    /// `a/file1.txt`, `a/b/file2.txt`, `c.txt`.
+    fn sample_tree() -> CompactPathTree {
+        let mut path = PathBuf::new();
+        path.push(Component::Normal("a".as_ref()));
+        path.push(Component::Normal("file1.txt".as_ref()));
+        path.push(Component::ParentDir);
+        path.push(Component::Normal("b".as_ref()));
+        path.push(Component::Normal("file2.txt".as_ref()));
+        path.push(Component::ParentDir);
+        path.push(Component::ParentDir);
+        path.push(Component::ParentDir);
+        path.push(Component::Normal("c.txt".as_ref()));
+        path.push(Component::ParentDir);
+
+        CompactPathTree {
+            root: PathBuf::from("/tmp/root"),
+            path,
+        }
+    }
+
+    #[test]
+    fn contains_finds_stored_paths_only() {
+        let tree = sample_tree();
+        let index = tree.index();
+
+        assert!(index.contains(&tree.root));
+        assert!(index.contains(&tree.root.join("a")));
+        assert!(index.contains(&tree.root.join("a/file1.txt")));
+        assert!(index.contains(&tree.root.join("a/b")));
+        assert!(index.contains(&tree.root.join("a/b/file2.txt")));
+        assert!(index.contains(&tree.root.join("c.txt")));
+
+        assert!(!index.contains(&tree.root.join("nope")));
+        assert!(!index.contains(&tree.root.join("a/nope")));
+        assert!(!index.contains(&tree.root.join("a/file1.txt/nope")));
+    }
+
+    #[test]
+    fn children_of_lists_direct_children_in_order() {
+        let tree = sample_tree();
+        let index = tree.index();
+
+        assert_eq!(
+            index.children_of(&tree.root).collect::<Vec<_>>(),
+            vec![tree.root.join("a"), tree.root.join("c.txt")],
+        );
+        assert_eq!(
+            index.children_of(&tree.root.join("a")).collect::<Vec<_>>(),
+            vec![tree.root.join("a/file1.txt"), tree.root.join("a/b"),],
+        );
+        assert_eq!(
+            index
+                .children_of(&tree.root.join("a/b"))
+                .collect::<Vec<_>>(),
+            vec![tree.root.join("a/b/file2.txt")],
+        );
+
+        // leaves and missing paths alike have no children
+        assert_eq!(index.children_of(&tree.root.join("c.txt")).count(), 0);
+        assert_eq!(index.children_of(&tree.root.join("nope")).count(), 0);
+    }
+
+    #[test]
+    fn subtree_seeks_to_the_right_range() {
+        let tree = sample_tree();
+        let index = tree.index();
+
+        assert_eq!(
+            index.subtree(&tree.root.join("a")).collect::<Vec<_>>(),
+            vec![
+                tree.root.join("a/file1.txt"),
+                tree.root.join("a/b"),
+                tree.root.join("a/b/file2.txt"),
+            ],
+        );
+        assert_eq!(
+            index.subtree(&tree.root.join("a/b")).collect::<Vec<_>>(),
+            vec![tree.root.join("a/b/file2.txt")],
+        );
+        assert_eq!(index.subtree(&tree.root.join("c.txt")).count(), 0);
+        assert_eq!(index.subtree(&tree.root.join("nope")).count(), 0);
+
+        // the whole tree, same order `CompactPathTree::iter` would produce
+        assert_eq!(
+            index.subtree(&tree.root).collect::<Vec<_>>(),
+            tree.iter().collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn build_does_not_panic_on_an_unbalanced_component_stream() {
+        // `CompactPathTree::read_from` rejects a stream like this, but
+        // `build` shouldn't trust that and panic if it's ever handed one
+        // anyway (e.g. a tree constructed some other way).
+        let mut path = PathBuf::new();
+        path.push(Component::Normal("a".as_ref()));
+        path.push(Component::ParentDir);
+        path.push(Component::ParentDir); // stray, unmatched by any `Normal`
+
+        let tree = CompactPathTree {
+            root: PathBuf::from("/tmp/root"),
+            path,
+        };
+
+        let index = tree.index();
+        assert!(index.contains(&tree.root.join("a")));
+    }
+}
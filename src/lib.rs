@@ -1,25 +1,54 @@
-use std::fs::DirEntry;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::{DirEntry, FileType, Metadata};
 use std::io;
 use std::path::{Component, Components, Path, PathBuf};
 
+mod format;
+pub mod ignore;
+mod index;
+
+pub use index::CompactPathTreeIndex;
+
+use format::os_str_to_bytes;
+
 /// A visitor that can determine which paths should be included and how errors
 /// are handled, or just view paths as the tree is constructed.
 pub trait PathVisitor {
     /// Determine whether the given entry should be included in the tree.
     ///
+    /// `file_type` is the entry's already-resolved `FileType`, so visitors
+    /// that only care about the kind of entry (file/dir/symlink) never need
+    /// to call `entry.metadata()` themselves, which on Unix is a second
+    /// stat-family syscall on top of the one `add_item` already makes.
+    /// Visitors that need size/mtime/etc. can still call `entry.metadata()`.
+    ///
+    /// `depth` is the number of `Normal` path components between the
+    /// traversal root and `entry`'s parent directory, i.e. the root's direct
+    /// children are at depth `0`.
+    ///
     /// When `Ok(true)` is returned, the entry is included. When `Ok(false)` is
     /// returned, the entry is omitted, including any children for directories.
     /// When `Err(..)` is returned, the item is omitted and
     /// `PathVisitor::handle_error` is used to determine whether the operation
     /// should fail or not.
-    fn filter(&mut self, _entry: &DirEntry) -> io::Result<bool> {
+    fn filter(
+        &mut self,
+        _entry: &DirEntry,
+        _file_type: FileType,
+        _depth: usize,
+    ) -> io::Result<bool> {
         Ok(true)
     }
 
     /// A general-purpose function for any logic involving included entries.
     /// This is called after `filter` and only for entries for which `filter`
     /// returned `Ok(true)`.
-    fn visit(&mut self, _entry: &DirEntry) -> io::Result<()> {
+    ///
+    /// See [`PathVisitor::filter`] for the meaning of `file_type` and `depth`.
+    fn visit(&mut self, _entry: &DirEntry, _file_type: FileType, _depth: usize) -> io::Result<()> {
         Ok(())
     }
 
@@ -55,78 +84,366 @@ pub trait PathVisitor {
     }
 }
 
-/// A compact immutable representation of the paths within a directory.
-#[derive(Clone, PartialEq, Eq)]
-pub struct CompactPathTree {
-    root: PathBuf,
+/// The `(device, inode)` pair used to recognize a previously-visited
+/// directory when following symlinks.
+///
+/// On Unix this is obtained directly from `MetadataExt`. Other platforms
+/// don't expose stable inode numbers through `std`, so the canonicalized
+/// path is used as a stand-in instead.
+#[cfg(unix)]
+type VisitedKey = (u64, u64);
+#[cfg(not(unix))]
+type VisitedKey = PathBuf;
+
+#[cfg(unix)]
+fn visited_key(target: &Path) -> io::Result<VisitedKey> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = target.metadata()?;
+    Ok((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn visited_key(target: &Path) -> io::Result<VisitedKey> {
+    target.canonicalize()
+}
+
+/// A symlink was followed back into a directory that's already an ancestor
+/// of it in the traversal, i.e. following it would recurse forever.
+///
+/// This is surfaced through [`PathVisitor::handle_error`] like any other IO
+/// error, rather than causing a stack overflow, and can be distinguished
+/// from other errors via [`SymlinkCycle::path`] or by downcasting.
+#[derive(Debug)]
+pub struct SymlinkCycle {
     path: PathBuf,
 }
 
-impl CompactPathTree {
+impl SymlinkCycle {
+    /// The resolved target that would have been visited again.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn into_io_error(self) -> io::Error {
+        io::Error::other(self)
+    }
+}
+
+impl fmt::Display for SymlinkCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "symlink cycle detected: `{}` has already been visited",
+            self.path.display()
+        )
+    }
+}
+
+impl Error for SymlinkCycle {}
+
+/// Comparator used by [`CompactPathTreeBuilder::sort_by`], boxed so
+/// `CompactPathTreeBuilder` and `Traversal` don't need to be generic over it.
+type SortComparator = Box<dyn FnMut(&DirEntry, &DirEntry) -> Ordering>;
+
+/// Builds a [`CompactPathTree`], configuring how the traversal behaves.
+#[derive(Default)]
+pub struct CompactPathTreeBuilder {
+    follow_symlinks: bool,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    sort_by: Option<SortComparator>,
+}
+
+impl CompactPathTreeBuilder {
+    /// Create a builder with the default configuration: symlinks are stored
+    /// but not followed, and there is no depth limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// When enabled, symlinks pointing at directories are resolved and
+    /// descended into instead of being stored as opaque leaves.
+    ///
+    /// Cycles are detected by tracking the `(device, inode)` of every
+    /// directory entered through a followed symlink (see [`VisitedKey`]); if
+    /// a followed symlink resolves to one of those directories while it's
+    /// still being traversed, a [`SymlinkCycle`] error is reported to
+    /// `PathVisitor::handle_error` instead of recursing forever. Directories
+    /// reached without following a symlink aren't tracked, so a symlink back
+    /// to such an ancestor is descended into once before the cycle is
+    /// reported.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Don't store files above this depth in the tree; see
+    /// [`PathVisitor::filter`] for how depth is counted. Directories are
+    /// always stored regardless of `min_depth`, since their name is needed
+    /// to reconstruct the real filesystem path of anything nested inside
+    /// them; only shallow files are actually omitted.
+    ///
+    /// Defaults to `0`, i.e. no entries are excluded.
+    pub fn min_depth(mut self, min_depth: usize) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Don't recurse into directories beyond this depth, where a directory's
+    /// depth counts its own name, i.e. one more than the `depth`
+    /// [`PathVisitor::filter`] is given for it. A directory at exactly
+    /// `max_depth` is still recorded, but its contents are skipped without
+    /// ever calling `read_dir` on it. The root's direct children are always
+    /// read.
+    ///
+    /// Defaults to `None`, i.e. no limit.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Sort each directory's entries before adding them to the tree, using
+    /// the given comparator.
+    ///
+    /// This makes traversal order (and therefore the stored tree and its
+    /// iterator) deterministic and reproducible across runs and
+    /// filesystems, at the cost of buffering each directory's entries
+    /// instead of streaming them. Leaving this unset preserves whatever
+    /// order `read_dir` happens to return.
+    pub fn sort_by(mut self, cmp: impl FnMut(&DirEntry, &DirEntry) -> Ordering + 'static) -> Self {
+        self.sort_by = Some(Box::new(cmp));
+        self
+    }
+
+    /// Sort each directory's entries by `file_name()`. A convenient preset
+    /// for [`CompactPathTreeBuilder::sort_by`].
+    pub fn sort_by_name(self) -> Self {
+        self.sort_by(|a, b| a.file_name().cmp(&b.file_name()))
+    }
+
+    /// Sort each directory's entries by the raw bytes of `file_name()`,
+    /// rather than going through `OsStr`'s platform-dependent ordering. A
+    /// convenient preset for [`CompactPathTreeBuilder::sort_by`].
+    pub fn sort_by_file_name_bytes(self) -> Self {
+        self.sort_by(|a, b| os_str_to_bytes(&a.file_name()).cmp(&os_str_to_bytes(&b.file_name())))
+    }
+
+    /// Construct a [`CompactPathTree`] by doing a depth-first traversal of
+    /// the given directory with this configuration.
+    pub fn build(
+        self,
+        root: PathBuf,
+        visitor: &mut impl PathVisitor,
+    ) -> io::Result<CompactPathTree> {
+        let mut path = PathBuf::new();
+        let mut traversal = Traversal {
+            follow_symlinks: self.follow_symlinks,
+            min_depth: self.min_depth,
+            max_depth: self.max_depth,
+            sort_by: self.sort_by,
+            visited: HashSet::new(),
+        };
+        traversal.build_tree(&mut path, &root, 0, visitor)?;
+        path.shrink_to_fit();
+
+        Ok(CompactPathTree { root, path })
+    }
+}
+
+/// Mutable state threaded through a single traversal; kept separate from
+/// `CompactPathTreeBuilder` since it's only meaningful while a traversal is
+/// in progress.
+struct Traversal {
+    follow_symlinks: bool,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    sort_by: Option<SortComparator>,
+    visited: HashSet<VisitedKey>,
+}
+
+impl Traversal {
     fn add_item(
+        &mut self,
         path: &mut PathBuf,
         item: &DirEntry,
+        depth: usize,
         visitor: &mut impl PathVisitor,
     ) -> io::Result<()> {
-        if !visitor.filter(&item)? {
+        // very important! try to get type before adding anything to the tree:
+        // if an error occurs and the visitor opts to ignore it, we don't want
+        // to leave the tree in a partially modified state. this also lets us
+        // hand the type to the visitor so it doesn't need its own stat call.
+        let typ = item.file_type()?;
+
+        if !visitor.filter(&item, typ, depth)? {
             return Ok(());
         }
 
-        visitor.visit(item)?;
+        visitor.visit(item, typ, depth)?;
 
-        // very important! try to get type before adding anything to the tree:
-        // if an error occurs and the visitor opts to ignore it, we don't want
-        // to leave the tree in a partially modified state
-        let typ = item.file_type()?;
+        // a symlink that we're about to follow into a directory is resolved
+        // up front so its `included` status accounts for the directory it
+        // actually becomes, not the symlink's own (non-directory) file type.
+        // only a missing target (a broken symlink) is treated as "not a
+        // directory" here; any other error (permission denied, a stale NFS
+        // handle, etc.) is a real failure and is surfaced through `?` just
+        // like `item.file_type()?` above, so it still reaches
+        // `PathVisitor::handle_error` via `visit_entry`.
+        let target_meta = if typ.is_symlink() && self.follow_symlinks {
+            match item.path().metadata() {
+                Ok(m) => Some(m),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        let target_is_dir = target_meta.as_ref().is_some_and(Metadata::is_dir);
+
+        // directories - including followed symlinks that resolve to one -
+        // below `min_depth` are still stored: their name is load-bearing for
+        // reconstructing the real filesystem path of anything nested inside
+        // them. only leaf entries are actually omitted.
+        let included = typ.is_dir() || target_is_dir || depth >= self.min_depth;
+        if included {
+            path.push(item.file_name());
+        }
 
-        path.push(item.file_name());
         if typ.is_dir() {
-            // as above, make sure we never leave the path in an illegal state
-            let r = Self::build_tree(path, &item.path(), visitor);
-            path.push(Component::ParentDir.as_os_str());
-            r?;
-        } else {
+            let child_depth = depth + 1;
+            if self.max_depth.is_some_and(|max| child_depth >= max) {
+                // the directory itself is still recorded, but we skip the
+                // `read_dir` for its contents entirely
+            } else {
+                // as above, make sure we never leave the path in an illegal
+                // state
+                let r = self.build_tree(path, &item.path(), child_depth, visitor);
+                if included {
+                    path.push(Component::ParentDir.as_os_str());
+                }
+                return r;
+            }
+        } else if typ.is_symlink() && self.follow_symlinks {
+            let r = self.follow_symlink(path, item, depth, target_meta, visitor);
+            if included {
+                path.push(Component::ParentDir.as_os_str());
+            }
+            return r;
+        }
+
+        if included {
             path.push(Component::ParentDir.as_os_str());
         }
 
         Ok(())
     }
 
+    fn follow_symlink(
+        &mut self,
+        path: &mut PathBuf,
+        item: &DirEntry,
+        depth: usize,
+        target_meta: Option<Metadata>,
+        visitor: &mut impl PathVisitor,
+    ) -> io::Result<()> {
+        let target = item.path();
+        if !target_meta.is_some_and(|m| m.is_dir()) {
+            // broken symlink, or resolved to something other than a
+            // directory: nothing to descend into
+            return Ok(());
+        }
+
+        let child_depth = depth + 1;
+        if self.max_depth.is_some_and(|max| child_depth >= max) {
+            return Ok(());
+        }
+
+        let key = visited_key(&target)?;
+        // `key` is needed again below to remove it once we're done, so it
+        // can't just be moved into `insert` - on `cfg(not(unix))` `VisitedKey`
+        // is a `PathBuf`, which isn't `Copy`, so this clone is load-bearing
+        // there even though it looks redundant on the `(u64, u64)` seen here.
+        #[allow(clippy::clone_on_copy)]
+        if !self.visited.insert(key.clone()) {
+            let path = target.canonicalize().unwrap_or(target);
+            return Err(SymlinkCycle { path }.into_io_error());
+        }
+
+        let r = self.build_tree(path, &target, child_depth, visitor);
+        self.visited.remove(&key);
+        r
+    }
+
+    fn visit_entry(
+        &mut self,
+        path: &mut PathBuf,
+        dir: &Path,
+        depth: usize,
+        item: io::Result<DirEntry>,
+        visitor: &mut impl PathVisitor,
+    ) -> io::Result<()> {
+        let item = match item.map_err(|e| visitor.handle_error(e, dir, None)) {
+            Ok(i) => i,
+            Err(None) => return Ok(()),
+            Err(Some(e)) => return Err(e),
+        };
+
+        match self
+            .add_item(path, &item, depth, visitor)
+            .map_err(|e| visitor.handle_error(e, dir, Some(&item)))
+        {
+            Err(Some(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     fn build_tree(
+        &mut self,
         path: &mut PathBuf,
         dir: &Path,
+        depth: usize,
         visitor: &mut impl PathVisitor,
     ) -> io::Result<()> {
-        for item in dir.read_dir()? {
-            let item = match item.map_err(|e| visitor.handle_error(e, dir, None)) {
-                Ok(i) => i,
-                Err(None) => continue,
-                Err(Some(e)) => return Err(e),
-            };
-
-            if let Err(Some(e)) = Self::add_item(path, &item, visitor)
-                .map_err(|e| visitor.handle_error(e, dir, Some(&item)))
-            {
-                return Err(e);
+        if self.sort_by.is_some() {
+            let mut entries = dir.read_dir()?.collect::<Vec<_>>();
+            entries.sort_by(|a, b| match (a, b) {
+                (Ok(a), Ok(b)) => (self.sort_by.as_mut().unwrap())(a, b),
+                // let `visit_entry` surface the actual IO error either way
+                _ => Ordering::Equal,
+            });
+
+            for item in entries {
+                self.visit_entry(path, dir, depth, item, visitor)?;
+            }
+        } else {
+            for item in dir.read_dir()? {
+                self.visit_entry(path, dir, depth, item, visitor)?;
             }
         }
 
         Ok(())
     }
+}
+
+/// A compact immutable representation of the paths within a directory.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CompactPathTree {
+    pub(crate) root: PathBuf,
+    pub(crate) path: PathBuf,
+}
 
+impl CompactPathTree {
     /// Construct a new `CompactPathTree` by doing a depth-first traversal of
     /// the given directory.
     ///
     /// The given visitor is used to determine which items should be included
     /// and what errors are fatal.
     ///
-    /// Symbolic links will be stored in the tree, but not followed.
+    /// Symbolic links will be stored in the tree, but not followed. Use
+    /// [`CompactPathTreeBuilder`] to change this and other traversal
+    /// behavior.
     pub fn new(root: PathBuf, visitor: &mut impl PathVisitor) -> io::Result<Self> {
-        let mut path = PathBuf::new();
-        Self::build_tree(&mut path, &root, visitor)?;
-        path.shrink_to_fit();
-
-        Ok(Self { root, path })
+        CompactPathTreeBuilder::new().build(root, visitor)
     }
 
     /// Get the underlying path this tree is represented as.
@@ -148,6 +465,17 @@ impl CompactPathTree {
     pub fn iter(&self) -> CompactPathTreeIter {
         self.into_iter()
     }
+
+    /// Build an index over this tree for random-access lookups, e.g.
+    /// [`CompactPathTreeIndex::contains`], [`CompactPathTreeIndex::children_of`]
+    /// and [`CompactPathTreeIndex::subtree`], instead of scanning [`Self::iter`].
+    ///
+    /// Building the index is `O(n)` in the number of stored entries; this is
+    /// an explicit, repeatable opt-in rather than something every tree pays
+    /// for, since not every consumer needs random access.
+    pub fn index(&self) -> CompactPathTreeIndex<'_> {
+        CompactPathTreeIndex::build(self)
+    }
 }
 
 impl<'a> IntoIterator for &'a CompactPathTree {
@@ -158,6 +486,7 @@ impl<'a> IntoIterator for &'a CompactPathTree {
         CompactPathTreeIter {
             current: self.root.clone(),
             components: self.path.components(),
+            remaining: None,
         }
     }
 }
@@ -165,13 +494,36 @@ impl<'a> IntoIterator for &'a CompactPathTree {
 pub struct CompactPathTreeIter<'a> {
     current: PathBuf,
     components: Components<'a>,
+    // bounds iteration to a prefix of `components`, used by
+    // `CompactPathTreeIndex::subtree` to stop at a descendant range instead
+    // of running to the end of the tree. `None` means unbounded.
+    remaining: Option<usize>,
+}
+
+impl<'a> CompactPathTreeIter<'a> {
+    pub(crate) fn bounded(current: PathBuf, components: Components<'a>, remaining: usize) -> Self {
+        Self {
+            current,
+            components,
+            remaining: Some(remaining),
+        }
+    }
 }
 
 impl<'a> Iterator for CompactPathTreeIter<'a> {
     type Item = PathBuf;
 
     fn next(&mut self) -> Option<Self::Item> {
-        for c in &mut self.components {
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+
+            let c = self.components.next()?;
+            if let Some(remaining) = &mut self.remaining {
+                *remaining -= 1;
+            }
+
             match c {
                 Component::ParentDir => {
                     self.current.pop();
@@ -183,7 +535,142 @@ impl<'a> Iterator for CompactPathTreeIter<'a> {
                 c => unreachable!("illegal component {:?} in path tree", c),
             }
         }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+
+    /// A directory under `std::env::temp_dir()`, removed when dropped, so
+    /// these tests can exercise real symlinks without an external crate for
+    /// temp-file handling.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "compact_path_tree_test_{}_{}_{}",
+                std::process::id(),
+                name,
+                n
+            ));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A visitor that accepts everything, relying entirely on
+    /// `PathVisitor`'s default method bodies.
+    struct AcceptAll;
+    impl PathVisitor for AcceptAll {}
+
+    #[test]
+    fn follows_symlinks_into_directories() {
+        let dir = TempDir::new("follow");
+        fs::create_dir(dir.path().join("real")).unwrap();
+        fs::write(dir.path().join("real/file.txt"), b"hi").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("link")).unwrap();
+
+        let tree = CompactPathTreeBuilder::new()
+            .follow_symlinks(true)
+            .build(dir.path().to_path_buf(), &mut AcceptAll)
+            .unwrap();
+
+        let paths: Vec<PathBuf> = tree.iter().collect();
+        assert!(paths.contains(&dir.path().join("link/file.txt")));
+    }
+
+    #[test]
+    fn detects_symlink_cycle() {
+        let dir = TempDir::new("cycle");
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        std::os::unix::fs::symlink(&sub, sub.join("loop")).unwrap();
+
+        let result = CompactPathTreeBuilder::new()
+            .follow_symlinks(true)
+            .build(dir.path().to_path_buf(), &mut AcceptAll);
+
+        let err = match result {
+            Err(e) => e,
+            Ok(_) => panic!("expected a symlink cycle error"),
+        };
+
+        assert!(err
+            .get_ref()
+            .is_some_and(|e| e.downcast_ref::<SymlinkCycle>().is_some()));
+    }
+
+    #[test]
+    fn min_depth_excludes_shallow_files_but_keeps_directories() {
+        let dir = TempDir::new("min_depth");
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::write(dir.path().join("a/deep.txt"), b"x").unwrap();
+        fs::write(dir.path().join("top.txt"), b"x").unwrap();
+
+        let tree = CompactPathTreeBuilder::new()
+            .min_depth(1)
+            .build(dir.path().to_path_buf(), &mut AcceptAll)
+            .unwrap();
+
+        let paths: Vec<PathBuf> = tree.iter().collect();
+        assert!(paths.contains(&dir.path().join("a")));
+        assert!(paths.contains(&dir.path().join("a/deep.txt")));
+        assert!(!paths.contains(&dir.path().join("top.txt")));
+    }
+
+    #[test]
+    fn max_depth_stops_recursion_but_keeps_the_boundary_directory() {
+        let dir = TempDir::new("max_depth");
+        fs::create_dir(dir.path().join("a")).unwrap();
+        fs::create_dir(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/b/deep.txt"), b"x").unwrap();
+
+        let tree = CompactPathTreeBuilder::new()
+            .max_depth(1)
+            .build(dir.path().to_path_buf(), &mut AcceptAll)
+            .unwrap();
+
+        let paths: Vec<PathBuf> = tree.iter().collect();
+        assert!(paths.contains(&dir.path().join("a")));
+        assert!(!paths.contains(&dir.path().join("a/b")));
+        assert!(!paths.contains(&dir.path().join("a/b/deep.txt")));
+    }
+
+    #[test]
+    fn sort_by_name_orders_siblings_deterministically() {
+        let dir = TempDir::new("sort_by_name");
+        for name in ["charlie.txt", "alpha.txt", "bravo.txt"] {
+            fs::write(dir.path().join(name), b"x").unwrap();
+        }
+
+        let tree = CompactPathTreeBuilder::new()
+            .sort_by_name()
+            .build(dir.path().to_path_buf(), &mut AcceptAll)
+            .unwrap();
 
-        None
+        assert_eq!(
+            tree.iter().collect::<Vec<_>>(),
+            vec![
+                dir.path().join("alpha.txt"),
+                dir.path().join("bravo.txt"),
+                dir.path().join("charlie.txt"),
+            ]
+        );
     }
 }